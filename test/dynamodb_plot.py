@@ -2,6 +2,7 @@
 # pip install boto3
 import os
 import sys
+import signal
 import numpy as np
 import pandas as pd
 import matplotlib.pyplot as plt
@@ -17,6 +18,8 @@ from boto3.dynamodb.conditions import Key
 DEVICE_ID = "m5-WiSUN"
 SENSOR_ID = "smartmeter"
 TABLE_NAME = "measurements"
+# Trueなら積算電力量が減っていたときに警告ではなくエラーで止める
+STRICT_CUMLATIVE_KWH = False
 
 
 def time_sequential_data_frame(item_list, tz):
@@ -58,6 +61,38 @@ def take_items_from_table(table, begin, end, tz):
     return time_sequential_data_frame([x["data"] for x in item_list], tz)
 
 
+def bar_width(x, default):
+    # 測定間隔の中央値を棒グラフの幅(日単位)にする
+    # 同じ測定値が何度も再送されるので重複を除いて測定時刻順に並べてから測る
+    x = sorted(set(x))
+    if len(x) < 2:
+        return default
+    median = pd.Series(x).diff().dropna().median()
+    return median / timedelta(days=1)
+
+
+def phase_amperes(df):
+    # どちらか片方の相しか無い場合は無い方を0とする
+    return df[['instant_ampere_R', 'instant_ampere_T']].dropna(how='all').fillna(0)
+
+
+def warn_decreasing(v):
+    # 積算値が減っていたらメーターの交換やリセット, 読み取り異常を疑う
+    # 送信時刻順に並んでいるので測定時刻順に並べ替えてから比べる
+    delta = v.sort_index().diff()
+    for (at, d) in delta[delta < 0].items():
+        message = "cumlative_kwh decreased by {:.2f} kWh at {}".format(-d, at)
+        if STRICT_CUMLATIVE_KWH:
+            raise ValueError(message)
+        print("warning: {}".format(message))
+
+
+def no_data(ax):
+    # データが無いグラフには"no data"と書いておく
+    ax.text(0.5, 0.5, 'no data', transform=ax.transAxes,
+            ha='center', va='center', fontsize=18, color='gray')
+
+
 def plot(df, filename_png, filename_csv, tz):
     #
     df = df.set_index('measured_at')
@@ -76,6 +111,7 @@ def plot(df, filename_png, filename_csv, tz):
         hour=0, minute=0, second=0, microsecond=0), df.index[-1]]
     #
     fig, axs = plt.subplots(3, 1, figsize=(48, 24))
+    empty_panels = []
     #
     axs[0].xaxis.set_major_locator(major_locator)
     axs[0].xaxis.set_major_formatter(major_formatter)
@@ -85,6 +121,7 @@ def plot(df, filename_png, filename_csv, tz):
     axs[0].set_ylabel('kWh')
     axs[0].set_title('cumulative amounts of electric power', fontsize=18)
     v = df['cumlative_kwh'].dropna()
+    warn_decreasing(v)
     x = v.index.tolist()
     y = v.tolist()
     if (len(y) > 2):
@@ -93,11 +130,14 @@ def plot(df, filename_png, filename_csv, tz):
     # axs[0].fill_between(x, y, color="lightblue", alpha=1.0)
     # 折れ線グラフ
     axs[0].plot(v, color="blue", marker='o', clip_on=False)
-    # 30分の幅
-    width = 30/(24*60)
+    # 測定間隔の幅(測れなければ30分)
+    width = bar_width(x, 30/(24*60))
     # 棒グラフ
     axs[0].bar(x, y, width=width, color="lightblue", align="edge")
     axs[0].grid(which='both', axis='both')
+    if len(y) == 0:
+        no_data(axs[0])
+        empty_panels.append('cumlative_kwh')
     #
     axs[1].xaxis.set_major_locator(major_locator)
     axs[1].xaxis.set_major_formatter(major_formatter)
@@ -110,19 +150,23 @@ def plot(df, filename_png, filename_csv, tz):
     y = df['instant_watt'].dropna().tolist()
     # 折れ線グラフ
 #    axs[1].plot(x, v, color="blue", marker='o', clip_on=False)
-    # 1分の幅
-    width = 1/(24*60)
+    # 測定間隔の幅(測れなければ1分)
+    width = bar_width(x, 1/(24*60))
     # 棒グラフ
     axs[1].bar(x, y, width=width, color="blue", align="edge")
     axs[1].grid(which='both', axis='both')
-    peak_index = np.argmax(y)
-    peak = y[peak_index]
-    axs[1].annotate(' {}\n {:.0f} W'.format(datetime.strftime(x[peak_index], '%H:%M:%S as %Z'), peak),
-                    xy=(x[peak_index], peak),
-                    size=15,
-                    xytext=(xlim[-1], peak+1),
-                    color='red',
-                    arrowprops=dict(color="red", arrowstyle="wedge,tail_width=1."))
+    if len(y) == 0:
+        no_data(axs[1])
+        empty_panels.append('instant_watt')
+    else:
+        peak_index = np.argmax(y)
+        peak = y[peak_index]
+        axs[1].annotate(' {}\n {:.0f} W'.format(datetime.strftime(x[peak_index], '%H:%M:%S as %Z'), peak),
+                        xy=(x[peak_index], peak),
+                        size=15,
+                        xytext=(xlim[-1], peak+1),
+                        color='red',
+                        arrowprops=dict(color="red", arrowstyle="wedge,tail_width=1."))
     #
     axs[2].xaxis.set_major_locator(major_locator)
     axs[2].xaxis.set_major_formatter(major_formatter)
@@ -132,9 +176,10 @@ def plot(df, filename_png, filename_csv, tz):
     axs[2].set_ylabel('A')
     axs[2].set_title(
         'instantaneous electric current', fontsize=18)
-    x = df['instant_ampere_R'].dropna().index.tolist()
-    r = df['instant_ampere_R'].dropna().tolist()
-    t = df['instant_ampere_T'].dropna().tolist()
+    ampere = phase_amperes(df)
+    x = ampere.index.tolist()
+    r = ampere['instant_ampere_R'].tolist()
+    t = ampere['instant_ampere_T'].tolist()
     r_plus_t = [a+b for (a, b) in zip(r, t)]
     # 折れ線グラフ
 #    axs[2].stackplot(x, r, t, colors=['lightcoral', 'lightblue'], alpha=1.0,
@@ -142,8 +187,8 @@ def plot(df, filename_png, filename_csv, tz):
 #    axs[2].plot(x, r, color="maroon", marker='o', clip_on=False)
 #    axs[2].plot(x, r_plus_t, color="blue", marker='o', clip_on=False)
     # 積み上げ棒グラフ
-    # 1分の幅
-    width = 1/(24*60)
+    # 測定間隔の幅(測れなければ1分)
+    width = bar_width(x, 1/(24*60))
     # R相電流
     axs[2].bar(x, r, width=width, color="tomato",
                align="edge", label="R-phase")
@@ -152,17 +197,24 @@ def plot(df, filename_png, filename_csv, tz):
                align="edge", label="T-phase", bottom=r)
     axs[2].legend(loc='upper left')
     axs[2].grid(which='both', axis='both')
-    peak_index = np.argmax(r_plus_t)
-    axs[2].annotate(' {}\n {:.1f} A'.format(datetime.strftime(x[peak_index], '%H:%M:%S as %Z'), r_plus_t[peak_index]),
-                    xy=(x[peak_index], r_plus_t[peak_index]),
-                    size=15,
-                    xytext=(xlim[-1], r_plus_t[peak_index]+1),
-                    color='red',
-                    arrowprops=dict(color="red", arrowstyle="wedge,tail_width=1."))
+    if len(r_plus_t) == 0:
+        no_data(axs[2])
+        empty_panels.append('instant_ampere')
+    else:
+        peak_index = np.argmax(r_plus_t)
+        axs[2].annotate(' {}\n {:.1f} A'.format(datetime.strftime(x[peak_index], '%H:%M:%S as %Z'), r_plus_t[peak_index]),
+                        xy=(x[peak_index], r_plus_t[peak_index]),
+                        size=15,
+                        xytext=(xlim[-1], r_plus_t[peak_index]+1),
+                        color='red',
+                        arrowprops=dict(color="red", arrowstyle="wedge,tail_width=1."))
     #
 #    fig.tight_layout()
     fig.savefig(filename_png)
     plt.close()
+    if len(empty_panels) > 0:
+        print("no data in {}: {}".format(
+            filename_png, ", ".join(empty_panels)))
 
 
 def take_first_and_last_items(table):
@@ -172,14 +224,25 @@ def take_first_and_last_items(table):
         'Limit': 1,
         'ScanIndexForward': True
     }
-    params['ScanIndexForward'] = True
-    response = table.query(**params)
-    first = response['Items'][0]
-    params['ScanIndexForward'] = False
-    response = table.query(**params)
-    last = response['Items'][0]
 
-    return (first['data'], last['data'])
+    def take_one(scan_index_forward):
+        first_page = dict(params, ScanIndexForward=scan_index_forward)
+        response = table.query(**first_page)
+        # Limitはフィルタ前に適用されるので条件に合う項目が出るまで読み進める
+        # 1件ずつ読むと項目数だけ問い合わせることになるので2ページ目以降はLimitを外す
+        next_pages = dict(first_page)
+        del next_pages['Limit']
+        while len(response['Items']) == 0 and 'LastEvaluatedKey' in response:
+            next_pages['ExclusiveStartKey'] = response['LastEvaluatedKey']
+            response = table.query(**next_pages)
+        if len(response['Items']) == 0:
+            return None
+        return response['Items'][0]['data']
+
+    first = take_one(True)
+    last = take_one(False)
+
+    return (first, last)
 
 
 def date_sequence(begin, end):
@@ -232,6 +295,10 @@ def run(region_name, aws_access_key_id, aws_secret_access_key):
     #
     tz = timezone('Asia/Tokyo')
     (first_item, last_item) = take_first_and_last_items(table)
+    # 空のテーブルなら何もしない
+    if first_item is None or last_item is None:
+        print("no data in table {}".format(TABLE_NAME))
+        return
     #
     first = parser.parse(first_item.get("measured_at")).astimezone(
         tz).replace(hour=0, minute=0, second=0, microsecond=0)
@@ -239,21 +306,46 @@ def run(region_name, aws_access_key_id, aws_secret_access_key):
     last = parser.parse(last_item.get("measured_at")).astimezone(
         tz).replace(hour=23, minute=59, second=59, microsecond=999999)
 
-    daylies = split_dayly(first, last)
-    for day in daylies:
-        begin = day[0]
-        end = day[-1] + timedelta(days=1) - timedelta(microseconds=1)
-        begin_ = begin.strftime('%Y-%m-%dT%H%M')
-        end_ = end.strftime('%H%M')
-        filename_png = "{}to{}.png".format(begin_, end_)
-        filename_csv = "{}to{}.csv".format(begin_, end_)
-        # 同名のファイルがあれば何もしない
-        if (os.path.isfile(filename_png)):
-            print("file {} is already exist, pass".format(filename_png))
-        else:
-            df = take_items_from_table(table, begin, end, tz)
-            plot(df, filename_png, filename_csv, tz)
-            print("----------")
+    # Ctrl-Cで書きかけのファイルが残らないように, その日の分を終えてから止める
+    interrupted = False
+
+    def on_sigint(signum, frame):
+        nonlocal interrupted
+        interrupted = True
+        # 問い合わせが終わらない時のために2回目のCtrl-Cはすぐに止める
+        signal.signal(signal.SIGINT, signal.default_int_handler)
+        print("interrupted, finishing the current day (press Ctrl-C again to abort)")
+    previous_handler = signal.signal(signal.SIGINT, on_sigint)
+    completed = 0
+
+    try:
+        daylies = split_dayly(first, last)
+        for day in daylies:
+            if interrupted:
+                break
+            begin = day[0]
+            end = day[-1] + timedelta(days=1) - timedelta(microseconds=1)
+            begin_ = begin.strftime('%Y-%m-%dT%H%M')
+            end_ = end.strftime('%H%M')
+            filename_png = "{}to{}.png".format(begin_, end_)
+            filename_csv = "{}to{}.csv".format(begin_, end_)
+            # 同名のファイルがあれば何もしない
+            if (os.path.isfile(filename_png)):
+                print("file {} is already exist, pass".format(filename_png))
+            else:
+                df = take_items_from_table(table, begin, end, tz)
+                # 1件も無い日は描画できないので飛ばす
+                if df.empty:
+                    print("no data in {} -> {}, pass".format(begin, end))
+                    continue
+                plot(df, filename_png, filename_csv, tz)
+                print("----------")
+                completed += 1
+    finally:
+        # run()を呼び出した側のCtrl-Cの扱いを元に戻す
+        signal.signal(signal.SIGINT, previous_handler)
+    if interrupted:
+        print("stopped after {} days".format(completed))
 
 
 if __name__ == '__main__':
@@ -0,0 +1,74 @@
+#!/usr/bin/env python3
+# $ python3 -m unittest test_dynamodb_plot
+import unittest
+from datetime import datetime, timedelta
+import pandas as pd
+from pytz import timezone
+from dynamodb_plot import bar_width, phase_amperes
+
+TZ = timezone('Asia/Tokyo')
+DEFAULT_WIDTH = 1/(24*60)
+
+
+def datetimes(count, minutes):
+    start = TZ.localize(datetime(2022, 12, 7))
+    return [start + timedelta(minutes=minutes*i) for i in range(count)]
+
+
+class TestBarWidth(unittest.TestCase):
+    def test_five_minutes_spacing(self):
+        x = datetimes(12, 5)
+        width = bar_width(x, DEFAULT_WIDTH)
+        self.assertAlmostEqual(width, 5/(24*60))
+        # 棒グラフが隣と重ならない
+        for (a, b) in zip(x, x[1:]):
+            self.assertLessEqual(width, (b - a) / timedelta(days=1))
+
+    def test_single_sample(self):
+        self.assertEqual(bar_width(datetimes(1, 5), DEFAULT_WIDTH),
+                         DEFAULT_WIDTH)
+
+    def test_all_duplicates(self):
+        x = datetimes(1, 5) * 3
+        self.assertEqual(bar_width(x, DEFAULT_WIDTH), DEFAULT_WIDTH)
+
+    def test_repeated_and_out_of_order(self):
+        # 30分値の一部が何度も再送され, 送信順に並んでいる
+        x = datetimes(6, 30)
+        x = x[:3] + [x[2]] * 5 + [x[4], x[3]] + [x[4]] * 4 + x[5:]
+        self.assertAlmostEqual(bar_width(x, DEFAULT_WIDTH), 30/(24*60))
+
+
+def ampere_frame(r, t):
+    df = pd.DataFrame({'measured_at': datetimes(len(r), 1),
+                       'instant_ampere_R': pd.Series(r, dtype='float64'),
+                       'instant_ampere_T': pd.Series(t, dtype='float64')})
+    return df.set_index('measured_at')
+
+
+class TestPhaseAmperes(unittest.TestCase):
+    def test_r_only(self):
+        df = ampere_frame([5.0, None, 3.0], [None, None, None])
+        ampere = phase_amperes(df)
+        self.assertEqual(ampere.index.tolist(), datetimes(3, 1)[0::2])
+        self.assertEqual(ampere['instant_ampere_R'].tolist(), [5.0, 3.0])
+        self.assertEqual(ampere['instant_ampere_T'].tolist(), [0.0, 0.0])
+
+    def test_t_only(self):
+        df = ampere_frame([None, None, None], [2.0, 4.0, None])
+        ampere = phase_amperes(df)
+        self.assertEqual(ampere.index.tolist(), datetimes(3, 1)[0:2])
+        self.assertEqual(ampere['instant_ampere_R'].tolist(), [0.0, 0.0])
+        self.assertEqual(ampere['instant_ampere_T'].tolist(), [2.0, 4.0])
+
+    def test_both_present(self):
+        df = ampere_frame([5.0, None, 3.0, None], [2.6, 1.0, None, None])
+        ampere = phase_amperes(df)
+        # 片方の相が欠けても, もう片方の値が別の時刻にずれない
+        self.assertEqual(ampere.index.tolist(), datetimes(4, 1)[0:3])
+        self.assertEqual(ampere['instant_ampere_R'].tolist(), [5.0, 0.0, 3.0])
+        self.assertEqual(ampere['instant_ampere_T'].tolist(), [2.6, 1.0, 0.0])
+
+
+if __name__ == '__main__':
+    unittest.main()